@@ -1,23 +1,36 @@
+mod metrics;
+
 use anyhow::Result;
-use std::collections::HashMap;
+use rand::distributions::{Distribution, Uniform};
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use metrics::Metrics;
 
+use geyser_grpc_connector::{
+    GeyserFilter, GrpcSourceConfig, Message as GeyserMessage, create_geyser_reconnecting_stream,
+};
 use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig};
 use solana_sdk::{
     address_lookup_table::AddressLookupTableAccount,
     commitment_config::CommitmentConfig,
     compute_budget::ComputeBudgetInstruction,
-    instruction::Instruction,
+    hash::Hash,
+    instruction::{Instruction, InstructionError},
     message::{VersionedMessage, v0},
+    packet::PACKET_DATA_SIZE,
     pubkey::Pubkey,
-    signature::{Keypair, Signer, read_keypair_file},
-    transaction::VersionedTransaction,
+    signature::{Keypair, Signature, Signer, read_keypair_file},
+    transaction::{TransactionError, VersionedTransaction},
 };
 
 use switchboard_on_demand_client::{
     crossbar::CrossbarClient,
     gateway::Gateway,
-    pull_feed::{FetchUpdateParams, PullFeed, SbContext},
+    pull_feed::{FetchUpdateParams, PullFeed, PullFeedAccountData, SbContext},
 };
 
 // ---------- FEEDS AS CONSTANTS ----------
@@ -37,6 +50,40 @@ const FEEDS: &[&str] = &[
     "DMhGWtLAKE5d56WdyHQxqeFncwUeqMEnuC2RvvZfbuur",
 ];
 
+// approximate, used to translate "slots since last update" into a human
+// staleness window for the `max_staleness` comparison, and back into a slot
+// gap when seeding the geyser watcher.
+const APPROX_MS_PER_SLOT: u64 = 400;
+
+// max compute units allowed in a single transaction.
+const CU_CEILING: u32 = 1_400_000;
+// rough per-feed CU guess used only for bin-packing decisions; the real
+// per-transaction limit is right-sized from simulation afterwards.
+const PER_FEED_CU_ESTIMATE: u32 = 300_000;
+
+#[derive(Clone, Copy)]
+struct FeeConfig {
+    percentile: f64,
+    floor: u64,
+    ceiling: u64,
+    jitter_ceiling: u64,
+}
+
+/// One feed's fetched update instruction, ready to be packed into a transaction.
+struct FeedUpdate {
+    feed: Pubkey,
+    ix: Instruction,
+    luts: Vec<AddressLookupTableAccount>,
+}
+
+/// A group of feed updates sized to fit in one transaction.
+struct TxGroup {
+    feeds: Vec<Pubkey>,
+    ixs: Vec<Instruction>,
+    luts: HashMap<Pubkey, AddressLookupTableAccount>,
+    cu_estimate: u32,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // ---------- config ----------
@@ -53,6 +100,82 @@ async fn main() -> Result<()> {
     let keypair_path = std::env::var("KEYPAIR").unwrap_or(default_kp);
     let payer: Keypair = read_keypair_file(&keypair_path)
         .map_err(|e| anyhow::anyhow!("read_keypair_file({}): {e}", keypair_path))?;
+    let payer = Arc::new(payer);
+
+    // fee config: percentile of recent prioritization fees to bid, clamped to
+    // [floor, ceiling] with a small random jitter on top so concurrent crankers
+    // don't all land on the exact same price.
+    let fee_config = FeeConfig {
+        percentile: std::env::var("SWB_FEE_PERCENTILE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(75.0),
+        floor: std::env::var("SWB_FEE_FLOOR")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1_000),
+        ceiling: std::env::var("SWB_FEE_CEILING")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(50_000),
+        jitter_ceiling: std::env::var("SWB_FEE_JITTER_CEILING")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(500),
+    };
+    if fee_config.floor > fee_config.ceiling {
+        anyhow::bail!(
+            "SWB_FEE_FLOOR ({}) must not be greater than SWB_FEE_CEILING ({})",
+            fee_config.floor,
+            fee_config.ceiling
+        );
+    }
+
+    // daemon config: how often to poll, and how fresh a feed must be before
+    // we skip cranking it again.
+    let mode = std::env::var("SWB_MODE").unwrap_or_else(|_| "once".to_string());
+    let interval = Duration::from_secs(
+        std::env::var("SWB_INTERVAL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(10),
+    );
+    let max_staleness = Duration::from_secs(
+        std::env::var("SWB_MAX_STALENESS_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(20),
+    );
+
+    // trigger config: in daemon mode, whether feeds are checked on a fixed
+    // interval (`poll`, the default) or reactively off a geyser gRPC stream
+    // (`grpc`), selected via `--mode=grpc|poll` on the command line.
+    let trigger_mode = trigger_mode_from_args();
+    let grpc_url = std::env::var("SWB_GRPC_URL").ok();
+    let grpc_slot_threshold = std::env::var("SWB_GRPC_SLOT_THRESHOLD")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(50);
+
+    // metrics: periodic stdout report cadence, plus an optional Prometheus
+    // `/metrics` HTTP endpoint (enabled by setting SWB_METRICS_ADDR).
+    let metrics_report_interval = Duration::from_secs(
+        std::env::var("SWB_METRICS_REPORT_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(30),
+    );
+    let metrics_addr = std::env::var("SWB_METRICS_ADDR").ok();
+    let metrics = Arc::new(Metrics::default());
+
+    if let Some(addr) = metrics_addr.clone() {
+        let metrics = Arc::clone(&metrics);
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve_prometheus(&addr, metrics).await {
+                eprintln!("metrics: prometheus endpoint stopped: {e:#}");
+            }
+        });
+    }
 
     // ---------- parse feeds ----------
     let feeds: Vec<Pubkey> = FEEDS
@@ -66,184 +189,820 @@ async fn main() -> Result<()> {
     }
 
     // ---------- shared clients ----------
-    let client = RpcClient::new_with_commitment(rpc_url, CommitmentConfig::confirmed());
+    let client = Arc::new(RpcClient::new_with_commitment(
+        rpc_url,
+        CommitmentConfig::confirmed(),
+    ));
     let ctx = SbContext::new();
     let gateway = Gateway::new(gateway_url);
     let crossbar = CrossbarClient::default();
 
-    // ---------- build all update ixs & merge LUTs ----------
-    // NOTE: This is effectively the "fetchUpdateManyIx" behavior: we fetch per-feed update ixs
+    match mode.as_str() {
+        "daemon" if trigger_mode == "grpc" => {
+            let grpc_url = grpc_url
+                .ok_or_else(|| anyhow::anyhow!("--mode=grpc requires SWB_GRPC_URL to be set"))?;
+            run_grpc(
+                &client,
+                &payer,
+                &gateway,
+                &crossbar,
+                &ctx,
+                &feeds,
+                &grpc_url,
+                grpc_slot_threshold,
+                max_staleness,
+                fee_config,
+                &metrics,
+                metrics_report_interval,
+            )
+            .await
+        }
+        "daemon" => {
+            run_daemon(
+                &client,
+                &payer,
+                &gateway,
+                &crossbar,
+                &ctx,
+                &feeds,
+                interval,
+                max_staleness,
+                fee_config,
+                &metrics,
+                metrics_report_interval,
+            )
+            .await
+        }
+        _ => {
+            crank_iteration(
+                &client, &payer, &gateway, &crossbar, &ctx, &feeds, max_staleness, fee_config,
+                &metrics,
+            )
+            .await?;
+            metrics.report();
+            Ok(())
+        }
+    }
+}
+
+/// Reads `--mode=grpc|poll` off argv; defaults to `poll`.
+fn trigger_mode_from_args() -> String {
+    std::env::args()
+        .find_map(|arg| arg.strip_prefix("--mode=").map(str::to_string))
+        .unwrap_or_else(|| "poll".to_string())
+}
+
+/// Loops `crank_iteration` on `interval` until SIGINT, logging per-iteration
+/// how many feeds were eligible vs. skipped as fresh.
+#[allow(clippy::too_many_arguments)]
+async fn run_daemon(
+    client: &Arc<RpcClient>,
+    payer: &Arc<Keypair>,
+    gateway: &Gateway,
+    crossbar: &CrossbarClient,
+    ctx: &SbContext,
+    feeds: &[Pubkey],
+    interval: Duration,
+    max_staleness: Duration,
+    fee_config: FeeConfig,
+    metrics: &Arc<Metrics>,
+    metrics_report_interval: Duration,
+) -> Result<()> {
+    println!(
+        "daemon mode: polling every {:?}, max_staleness={:?}",
+        interval, max_staleness
+    );
+    let mut ticker = tokio::time::interval(interval);
+    let mut report_ticker = tokio::time::interval(metrics_report_interval);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if let Err(e) = crank_iteration(
+                    client, payer, gateway, crossbar, ctx, feeds, max_staleness, fee_config, metrics,
+                )
+                .await
+                {
+                    eprintln!("crank iteration failed: {e:#}");
+                }
+            }
+            _ = report_ticker.tick() => {
+                metrics.report();
+            }
+            _ = tokio::signal::ctrl_c() => {
+                println!("received SIGINT, shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Same shutdown/logging contract as `run_daemon`, but feeds are cranked
+/// reactively off a geyser gRPC stream instead of a fixed interval: a watched
+/// feed is enqueued the moment its own feed account changes, or once
+/// `slot_threshold` slots have elapsed since it was last seen updated. Only
+/// the feed accounts are watched; no other accounts trigger a crank.
+#[allow(clippy::too_many_arguments)]
+async fn run_grpc(
+    client: &Arc<RpcClient>,
+    payer: &Arc<Keypair>,
+    gateway: &Gateway,
+    crossbar: &CrossbarClient,
+    ctx: &SbContext,
+    feeds: &[Pubkey],
+    grpc_url: &str,
+    slot_threshold: u64,
+    max_staleness: Duration,
+    fee_config: FeeConfig,
+    metrics: &Arc<Metrics>,
+    metrics_report_interval: Duration,
+) -> Result<()> {
+    println!("grpc mode: watching {grpc_url}, slot_threshold={slot_threshold}");
+
+    let (trigger_tx, mut trigger_rx) = mpsc::channel::<Pubkey>(feeds.len().max(1) * 4);
+
+    let watched_feeds = feeds.to_vec();
+    let watch_url = grpc_url.to_string();
+    let watch_client = Arc::clone(client);
+    tokio::spawn(async move {
+        if let Err(e) =
+            watch_geyser(&watch_client, &watch_url, &watched_feeds, slot_threshold, trigger_tx)
+                .await
+        {
+            eprintln!("geyser stream ended: {e:#}");
+        }
+    });
+
+    let mut report_ticker = tokio::time::interval(metrics_report_interval);
+
+    loop {
+        tokio::select! {
+            Some(feed) = trigger_rx.recv() => {
+                if let Err(e) = crank_iteration(
+                    client, payer, gateway, crossbar, ctx, &[feed], max_staleness, fee_config, metrics,
+                )
+                .await
+                {
+                    eprintln!("crank iteration (grpc trigger for {feed}) failed: {e:#}");
+                }
+            }
+            _ = report_ticker.tick() => {
+                metrics.report();
+            }
+            _ = tokio::signal::ctrl_c() => {
+                println!("received SIGINT, shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Subscribes to account updates for `feeds` plus slot notifications over a
+/// reconnecting geyser gRPC stream, sending a feed's pubkey to `tx` whenever
+/// it changes or goes stale by slot count. Self-heals across disconnects via
+/// `create_geyser_reconnecting_stream`'s built-in retry/timeout handling.
+///
+/// `last_seen_slot` is seeded from each feed's actual on-chain last-updated
+/// slot before the stream loop starts; otherwise every feed would appear to
+/// have `last_seen_slot == 0` and get enqueued on the very first slot
+/// notification, and keep getting re-enqueued every slot after that since a
+/// genuinely stale feed won't produce an `Account` message until we crank it
+/// ourselves.
+async fn watch_geyser(
+    client: &RpcClient,
+    grpc_url: &str,
+    feeds: &[Pubkey],
+    slot_threshold: u64,
+    tx: mpsc::Sender<Pubkey>,
+) -> Result<()> {
+    let source = GrpcSourceConfig::new(grpc_url.to_string(), None, None, Default::default());
+    let filter = GeyserFilter::accounts_and_slots(feeds);
+    let (_handle, mut stream) = create_geyser_reconnecting_stream(source, filter);
+
+    let mut current_slot: u64 = client.get_slot().await?;
+    let mut last_seen_slot: HashMap<Pubkey, u64> = HashMap::new();
+    for &feed in feeds {
+        match feed_age(client, feed, current_slot).await {
+            Ok(age) => {
+                let slot_gap = age.as_millis() as u64 / APPROX_MS_PER_SLOT;
+                last_seen_slot.insert(feed, current_slot.saturating_sub(slot_gap));
+            }
+            Err(e) => eprintln!("watch_geyser: seeding last_seen_slot for {feed} failed: {e:#}"),
+        }
+    }
+
+    while let Some(message) = stream.recv().await {
+        match message {
+            GeyserMessage::Slot(slot_update) => {
+                current_slot = slot_update.slot;
+                for &feed in feeds {
+                    let last = *last_seen_slot.get(&feed).unwrap_or(&current_slot);
+                    if current_slot.saturating_sub(last) >= slot_threshold {
+                        // Bump immediately so we enqueue this feed once per
+                        // `slot_threshold` slots rather than on every tick
+                        // until an `Account` update (which may never come
+                        // for a feed that's stale precisely because nobody
+                        // is cranking it) bumps it for us.
+                        last_seen_slot.insert(feed, current_slot);
+                        let _ = tx.send(feed).await;
+                    }
+                }
+            }
+            GeyserMessage::Account(account_update) => {
+                if feeds.contains(&account_update.pubkey) {
+                    last_seen_slot.insert(account_update.pubkey, current_slot);
+                    let _ = tx.send(account_update.pubkey).await;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    anyhow::bail!("geyser stream closed unexpectedly")
+}
+
+/// Runs one crank pass: skip feeds that aren't stale yet, fetch update ixs
+/// for the rest concurrently, then bin-pack them into as many transactions as
+/// the CU and size limits require and build/simulate/send each one.
+#[allow(clippy::too_many_arguments)]
+async fn crank_iteration(
+    client: &Arc<RpcClient>,
+    payer: &Arc<Keypair>,
+    gateway: &Gateway,
+    crossbar: &CrossbarClient,
+    ctx: &SbContext,
+    feeds: &[Pubkey],
+    max_staleness: Duration,
+    fee_config: FeeConfig,
+    metrics: &Arc<Metrics>,
+) -> Result<()> {
+    // ---------- staleness gating ----------
+    let current_slot = client.get_slot().await?;
+
+    let age_tasks = feeds.iter().map(|&feed| {
+        let client = Arc::clone(client);
+        tokio::spawn(async move { (feed, feed_age(&client, feed, current_slot).await) })
+    });
+    let age_results = futures::future::join_all(age_tasks).await;
+
+    let mut eligible: Vec<Pubkey> = Vec::with_capacity(feeds.len());
+    let mut skipped = 0usize;
+
+    for joined in age_results {
+        let (feed, result) = joined?;
+        match result {
+            Ok(age) if age < max_staleness => {
+                skipped += 1;
+            }
+            Ok(_) => eligible.push(feed),
+            Err(e) => {
+                // can't tell how fresh it is; be conservative and crank it.
+                eprintln!("warn: could not read age for feed {feed}: {e:#}; cranking anyway");
+                eligible.push(feed);
+            }
+        }
+    }
+
+    println!(
+        "iteration: {} eligible, {} skipped (fresh) of {} feeds",
+        eligible.len(),
+        skipped,
+        feeds.len()
+    );
+
+    if eligible.is_empty() {
+        return Ok(());
+    }
+
+    // ---------- fetch per-feed update ixs concurrently ----------
+    // NOTE: this is effectively the "fetchUpdateManyIx" behavior: we fetch per-feed update ixs
     // and send them together in ONE transaction.
-    let mut all_update_ixs: Vec<Instruction> = Vec::with_capacity(feeds.len());
-    let mut lut_map: HashMap<Pubkey, AddressLookupTableAccount> = HashMap::new();
+    let tasks = eligible.iter().map(|&feed| {
+        let client = Arc::clone(client);
+        let ctx = ctx.clone();
+        let gateway = gateway.clone();
+        let crossbar = crossbar.clone();
+        let payer_pubkey = payer.pubkey();
+        let metrics = Arc::clone(metrics);
+        tokio::spawn(async move {
+            let start = Instant::now();
+            let result =
+                fetch_update_ix_with_backoff(&ctx, &client, &gateway, &crossbar, feed, payer_pubkey)
+                    .await;
+            metrics.fetch_update_ix.record(start.elapsed());
+            (feed, result)
+        })
+    });
 
-    for &feed in &feeds {
-        println!("Preparing update ix for feed: {feed}");
-        let (update_ix, _responses, _num_ok, luts) = PullFeed::fetch_update_ix(
+    let results = futures::future::join_all(tasks).await;
+
+    let mut updates: Vec<FeedUpdate> = Vec::with_capacity(eligible.len());
+
+    for joined in results {
+        let (feed, result) = joined?;
+        match result {
+            Ok((ix, luts, num_ok)) => {
+                metrics.record_num_ok(feed, num_ok);
+                updates.push(FeedUpdate { feed, ix, luts });
+            }
+            Err(e) => {
+                eprintln!("skipping feed {feed} after repeated failures: {e:#}");
+            }
+        }
+    }
+
+    if updates.is_empty() {
+        anyhow::bail!("all eligible feeds failed to produce an update ix");
+    }
+
+    // ---------- dynamic priority fee ----------
+    // Sample getRecentPrioritizationFees over the writable accounts these txs
+    // actually touch and bid the configured percentile, plus jitter so
+    // concurrent crankers don't all converge on the same price. One fee is
+    // reused across every group below.
+    let writable_accounts: Vec<Pubkey> = updates
+        .iter()
+        .flat_map(|u| u.ix.accounts.iter())
+        .filter(|meta| meta.is_writable)
+        .map(|meta| meta.pubkey)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    let mut recent_fees: Vec<u64> = match client
+        .get_recent_prioritization_fees(&writable_accounts)
+        .await
+    {
+        Ok(fees) => fees.into_iter().map(|f| f.prioritization_fee).collect(),
+        Err(e) => {
+            // getRecentPrioritizationFees caps the account list at 128 pubkeys
+            // server-side, so this can start happening once the feed list
+            // grows; make the degraded fallback to `floor` visible instead of
+            // looking indistinguishable from "the market is just cheap".
+            eprintln!(
+                "warn: get_recent_prioritization_fees failed: {e:#}; falling back to fee floor ({})",
+                fee_config.floor
+            );
+            Vec::new()
+        }
+    };
+    recent_fees.sort_unstable();
+
+    let percentile_fee = if recent_fees.is_empty() {
+        fee_config.floor
+    } else {
+        let idx = (((fee_config.percentile / 100.0) * (recent_fees.len() - 1) as f64).round()
+            as usize)
+            .min(recent_fees.len() - 1);
+        recent_fees[idx]
+    };
+
+    let jitter = if fee_config.jitter_ceiling > 0 {
+        Uniform::from(0..fee_config.jitter_ceiling).sample(&mut rand::thread_rng())
+    } else {
+        0
+    };
+    let priority_fee = (percentile_fee + jitter).clamp(fee_config.floor, fee_config.ceiling);
+
+    let blockhash_start = Instant::now();
+    let latest_blockhash = client.get_latest_blockhash().await?;
+    metrics.blockhash_fetch.record(blockhash_start.elapsed());
+
+    // ---------- greedily bin-pack feeds into transactions ----------
+    // Each group must respect both the CU ceiling and the serialized v0
+    // message size limit; LUTs are merged only within a group.
+    let packed_feeds = updates.len();
+    let groups =
+        pack_feed_updates(payer.as_ref(), updates, PER_FEED_CU_ESTIMATE, latest_blockhash)?;
+    let total_groups = groups.len();
+    println!("packed {packed_feeds} eligible feeds into {total_groups} transaction(s)");
+
+    // ---------- build, simulate, send each group concurrently ----------
+    let send_tasks = groups.into_iter().map(|group| {
+        let client = Arc::clone(client);
+        let payer = Arc::clone(payer);
+        let metrics = Arc::clone(metrics);
+        tokio::spawn(async move {
+            send_group(
+                &client,
+                payer.as_ref(),
+                group,
+                priority_fee,
+                latest_blockhash,
+                &metrics,
+            )
+            .await
+        })
+    });
+
+    let send_results = futures::future::join_all(send_tasks).await;
+
+    let mut confirmed = 0usize;
+    for joined in send_results {
+        let result = joined?;
+        match result {
+            Ok(sent) => {
+                for (feeds, sig) in sent {
+                    confirmed += 1;
+                    println!("✅ Cranked {} feeds in tx -> {sig}", feeds.len());
+                    for f in &feeds {
+                        println!("  • {f}");
+                    }
+                }
+            }
+            Err(e) => eprintln!("transaction failed: {e:#}"),
+        }
+    }
+
+    println!(
+        "iteration complete: {confirmed} transaction(s) confirmed from {total_groups} packed group(s)"
+    );
+
+    Ok(())
+}
+
+/// Greedily groups `updates`, in order, into transactions such that each
+/// group stays within the CU ceiling and the serialized v0 message size
+/// limit. Each feed is tried only against the most recent group and starts a
+/// new one if it doesn't fit; earlier groups are not revisited, so the group
+/// count is not guaranteed to be minimal. A feed that can't fit alone is a
+/// hard error.
+///
+/// `per_feed_cu` is a fixed, compile-time estimate (`PER_FEED_CU_ESTIMATE`)
+/// used only to decide how many feeds to pack per group; it is not a
+/// measured value, so it can't tell us a particular feed's *real* CU usage
+/// exceeds the ceiling here. That's caught later: `send_group` simulates
+/// each group before sending and right-sizes the CU limit from
+/// `units_consumed`, so a feed whose real usage is too large surfaces there
+/// as a simulation failure rather than as a packing error.
+fn pack_feed_updates(
+    payer: &Keypair,
+    updates: Vec<FeedUpdate>,
+    per_feed_cu: u32,
+    blockhash: Hash,
+) -> Result<Vec<TxGroup>> {
+    let mut groups: Vec<TxGroup> = Vec::new();
+
+    for update in updates {
+        let solo_ixs = std::slice::from_ref(&update.ix);
+        let solo_size = estimate_tx_size(payer, solo_ixs, &update.luts, blockhash)?;
+        if solo_size > PACKET_DATA_SIZE {
+            anyhow::bail!(
+                "feed {} alone exceeds the tx size limit ({solo_size}B > {PACKET_DATA_SIZE}B)",
+                update.feed
+            );
+        }
+
+        let fits_cu = groups
+            .last()
+            .is_some_and(|g| g.cu_estimate + per_feed_cu <= CU_CEILING);
+
+        let fits_size = if fits_cu {
+            let group = groups.last().unwrap();
+            let mut trial_ixs = group.ixs.clone();
+            trial_ixs.push(update.ix.clone());
+            let mut trial_luts: Vec<AddressLookupTableAccount> =
+                group.luts.values().cloned().collect();
+            for lut in &update.luts {
+                if !group.luts.contains_key(&lut.key) {
+                    trial_luts.push(lut.clone());
+                }
+            }
+            estimate_tx_size(payer, &trial_ixs, &trial_luts, blockhash)? <= PACKET_DATA_SIZE
+        } else {
+            false
+        };
+
+        if fits_cu && fits_size {
+            let group = groups.last_mut().unwrap();
+            group.feeds.push(update.feed);
+            group.ixs.push(update.ix);
+            group.cu_estimate += per_feed_cu;
+            for lut in update.luts {
+                group.luts.entry(lut.key).or_insert(lut);
+            }
+        } else {
+            let mut luts = HashMap::new();
+            for lut in update.luts {
+                luts.insert(lut.key, lut);
+            }
+            groups.push(TxGroup {
+                feeds: vec![update.feed],
+                ixs: vec![update.ix],
+                luts,
+                cu_estimate: per_feed_cu,
+            });
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Serialized size (bytes) of a versioned transaction carrying `ixs` plus a
+/// placeholder compute-budget pair, signed by `payer` alone.
+fn estimate_tx_size(
+    payer: &Keypair,
+    ixs: &[Instruction],
+    luts: &[AddressLookupTableAccount],
+    blockhash: Hash,
+) -> Result<usize> {
+    let mut full_ixs = Vec::with_capacity(2 + ixs.len());
+    full_ixs.push(ComputeBudgetInstruction::set_compute_unit_limit(
+        CU_CEILING,
+    ));
+    full_ixs.push(ComputeBudgetInstruction::set_compute_unit_price(u64::MAX));
+    full_ixs.extend_from_slice(ixs);
+
+    let msg = v0::Message::try_compile(&payer.pubkey(), &full_ixs, luts, blockhash)?;
+    let vtx = VersionedTransaction::try_new(VersionedMessage::V0(msg), &[payer])?;
+    Ok(bincode::serialize(&vtx)?.len())
+}
+
+/// True if a simulation error is the transaction running out of compute
+/// units, as opposed to some other program/account failure that splitting
+/// the group wouldn't fix.
+fn is_cu_exceeded(err: &TransactionError) -> bool {
+    matches!(
+        err,
+        TransactionError::InstructionError(_, InstructionError::ComputationalBudgetExceeded)
+    )
+}
+
+/// Splits a packed group roughly in half by feed count, dividing its CU
+/// estimate proportionally. LUTs aren't re-scoped per half (both halves just
+/// inherit the parent's merged set) since a superset of LUTs a smaller
+/// message already fit with only shrinks the serialized size further.
+fn split_group(group: TxGroup) -> (TxGroup, TxGroup) {
+    let mid = group.feeds.len() / 2;
+    let per_feed_cu = group.cu_estimate / group.feeds.len() as u32;
+
+    let TxGroup {
+        mut feeds,
+        mut ixs,
+        luts,
+        ..
+    } = group;
+    let feeds_b = feeds.split_off(mid);
+    let ixs_b = ixs.split_off(mid);
+
+    let cu_a = per_feed_cu * feeds.len() as u32;
+    let cu_b = per_feed_cu * feeds_b.len() as u32;
+
+    (
+        TxGroup {
+            feeds,
+            ixs,
+            luts: luts.clone(),
+            cu_estimate: cu_a,
+        },
+        TxGroup {
+            feeds: feeds_b,
+            ixs: ixs_b,
+            luts,
+            cu_estimate: cu_b,
+        },
+    )
+}
+
+/// Builds, simulates, and sends one packed group: simulates first to
+/// right-size the compute unit limit from `units_consumed`, then sends.
+///
+/// The CU estimate used for packing is a fixed per-feed guess, so a
+/// particular bundle of feeds can still simulate over the ceiling. Rather
+/// than dropping every feed in the group for that, a compute-budget-exceeded
+/// simulation failure on a group of more than one feed is treated as "the
+/// guess was too optimistic for this bundle": the group is split in half and
+/// each half is retried independently (recursing until either side succeeds
+/// or is down to a single feed, which surfaces as a real error).
+fn send_group<'a>(
+    client: &'a Arc<RpcClient>,
+    payer: &'a Keypair,
+    group: TxGroup,
+    priority_fee: u64,
+    blockhash: Hash,
+    metrics: &'a Metrics,
+) -> futures::future::BoxFuture<'a, Result<Vec<(Vec<Pubkey>, Signature)>>> {
+    Box::pin(async move {
+        let merged_luts: Vec<AddressLookupTableAccount> = group.luts.values().cloned().collect();
+
+        let build_ixs = |cu_limit: u32| -> Vec<Instruction> {
+            let mut ixs: Vec<Instruction> = Vec::with_capacity(2 + group.ixs.len());
+            ixs.push(ComputeBudgetInstruction::set_compute_unit_limit(cu_limit));
+            ixs.push(ComputeBudgetInstruction::set_compute_unit_price(
+                priority_fee,
+            ));
+            ixs.extend(group.ixs.iter().cloned());
+            ixs
+        };
+
+        // ---------- compile, simulate ----------
+        let sim_ixs = build_ixs(group.cu_estimate.min(CU_CEILING));
+        let sim_msg =
+            v0::Message::try_compile(&payer.pubkey(), &sim_ixs, &merged_luts, blockhash)?;
+        let sim_vtx = VersionedTransaction::try_new(VersionedMessage::V0(sim_msg), &[payer])?;
+
+        let sim_start = Instant::now();
+        let sim = client
+            .simulate_transaction_with_config(
+                &sim_vtx,
+                RpcSimulateTransactionConfig {
+                    sig_verify: true,
+                    replace_recent_blockhash: false,
+                    commitment: Some(CommitmentConfig::processed()),
+                    encoding: None,
+                    accounts: None,
+                    min_context_slot: None,
+                    inner_instructions: true,
+                },
+            )
+            .await?;
+        metrics.simulation.record(sim_start.elapsed());
+
+        if let Some(logs) = sim.value.logs.clone() {
+            println!("--- simulation logs ({} feeds) ---", group.feeds.len());
+            for l in logs {
+                println!("{l}");
+            }
+            println!("----------------------------------");
+        }
+        if let Some(err) = sim.value.err.clone() {
+            metrics
+                .sim_failures
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+            if is_cu_exceeded(&err) && group.feeds.len() > 1 {
+                let n = group.feeds.len();
+                println!(
+                    "warn: group of {n} feeds exceeded the CU limit in simulation; splitting and retrying"
+                );
+                let (left, right) = split_group(group);
+                let (left_result, right_result) = tokio::join!(
+                    send_group(client, payer, left, priority_fee, blockhash, metrics),
+                    send_group(client, payer, right, priority_fee, blockhash, metrics),
+                );
+
+                let mut sent = Vec::new();
+                for result in [left_result, right_result] {
+                    match result {
+                        Ok(mut s) => sent.append(&mut s),
+                        Err(e) => eprintln!("split retry failed: {e:#}"),
+                    }
+                }
+                return Ok(sent);
+            }
+
+            anyhow::bail!("simulation failed: {err:?}");
+        }
+
+        // right-size the compute unit limit from what simulation actually
+        // consumed, plus a safety margin, instead of the coarse packing estimate.
+        let sized_cu_limit = sim
+            .value
+            .units_consumed
+            .map(|units| ((units as f64) * 1.15).ceil() as u32)
+            .unwrap_or(group.cu_estimate)
+            .min(CU_CEILING);
+
+        let ixs = build_ixs(sized_cu_limit);
+        let v0_msg = v0::Message::try_compile(&payer.pubkey(), &ixs, &merged_luts, blockhash)?;
+        let vtx = VersionedTransaction::try_new(VersionedMessage::V0(v0_msg), &[payer])?;
+
+        let send_start = Instant::now();
+        match client.send_and_confirm_transaction(&vtx).await {
+            Ok(sig) => {
+                metrics.send_and_confirm.record(send_start.elapsed());
+                metrics
+                    .tx_confirmed
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                Ok(vec![(group.feeds, sig)])
+            }
+            Err(e) => {
+                metrics.send_and_confirm.record(send_start.elapsed());
+                metrics
+                    .tx_dropped
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                Err(e.into())
+            }
+        }
+    })
+}
+
+/// Age of a feed's last on-chain update, derived from the slot gap between
+/// `current_slot` and the feed's last-updated slot.
+async fn feed_age(client: &RpcClient, feed: Pubkey, current_slot: u64) -> Result<Duration> {
+    let account = client.get_account(&feed).await?;
+    let data = PullFeedAccountData::parse(&account.data)
+        .map_err(|e| anyhow::anyhow!("parse feed account {feed}: {e:?}"))?;
+    let slot_gap = current_slot.saturating_sub(data.result.slot);
+    Ok(Duration::from_millis(slot_gap * APPROX_MS_PER_SLOT))
+}
+
+/// `PullFeed::fetch_update_ix` with exponential backoff, since a single
+/// stalled gateway shouldn't take down the whole iteration.
+async fn fetch_update_ix_with_backoff(
+    ctx: &SbContext,
+    client: &RpcClient,
+    gateway: &Gateway,
+    crossbar: &CrossbarClient,
+    feed: Pubkey,
+    payer_pubkey: Pubkey,
+) -> Result<(Instruction, Vec<AddressLookupTableAccount>, u32)> {
+    const MAX_ATTEMPTS: u32 = 5;
+    const BASE_DELAY: Duration = Duration::from_millis(250);
+
+    let mut attempt = 0;
+    loop {
+        let result = PullFeed::fetch_update_ix(
             ctx.clone(),
-            &client,
+            client,
             FetchUpdateParams {
                 feed,
-                payer: payer.pubkey(),
+                payer: payer_pubkey,
                 gateway: gateway.clone(),
                 crossbar: Some(crossbar.clone()),
                 num_signatures: Some(1), // tune as you wish
                 debug: Some(false),
             },
         )
-        .await?;
-
-        all_update_ixs.push(update_ix);
-
-        // merge LUTs by key to avoid duplicates
-        for lut in luts {
-            lut_map.entry(lut.key).or_insert(lut);
+        .await;
+
+        match result {
+            Ok((update_ix, _responses, num_ok, luts)) => return Ok((update_ix, luts, num_ok)),
+            Err(e) if attempt + 1 < MAX_ATTEMPTS => {
+                let delay = BASE_DELAY * 2u32.pow(attempt);
+                eprintln!(
+                    "fetch_update_ix failed for feed {feed} (attempt {}/{MAX_ATTEMPTS}): {e}; retrying in {delay:?}",
+                    attempt + 1
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(anyhow::anyhow!("fetch_update_ix for feed {feed}: {e}")),
         }
     }
+}
 
-    let merged_luts: Vec<AddressLookupTableAccount> = lut_map.into_values().collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::system_instruction;
 
-    // ---------- compute budget (single tx for many feeds) ----------
-    // Max per-tx CU is 1.4M; target ~300k per feed (rough heuristic).
-    let per_feed_cu: u32 = 300_000;
-    let mut cu_limit = per_feed_cu.saturating_mul(feeds.len() as u32);
-    if cu_limit < 300_000 {
-        cu_limit = 300_000;
-    }
-    if cu_limit > 1_400_000 {
-        cu_limit = 1_400_000;
+    fn dummy_update(feed: Pubkey, payer: &Pubkey, min_data_len: usize) -> FeedUpdate {
+        let to = Pubkey::new_unique();
+        let mut ix = system_instruction::transfer(payer, &to, 1);
+        if min_data_len > ix.data.len() {
+            ix.data.resize(min_data_len, 0);
+        }
+        FeedUpdate {
+            feed,
+            ix,
+            luts: vec![],
+        }
     }
 
-    let compute_ixes = vec![
-        ComputeBudgetInstruction::set_compute_unit_limit(cu_limit),
-        // adjust price to your preference / network conditions
-        ComputeBudgetInstruction::set_compute_unit_price(5_000),
-    ];
+    #[test]
+    fn packs_feeds_that_fit_into_one_group() {
+        let payer = Keypair::new();
+        let updates = (0..3)
+            .map(|_| dummy_update(Pubkey::new_unique(), &payer.pubkey(), 0))
+            .collect();
 
-    let latest_blockhash = client.get_latest_blockhash().await?;
+        let groups =
+            pack_feed_updates(&payer, updates, PER_FEED_CU_ESTIMATE, Hash::default()).unwrap();
 
-    let mut ixs: Vec<Instruction> = Vec::with_capacity(2 + all_update_ixs.len());
-    ixs.extend(compute_ixes);
-    ixs.extend(all_update_ixs);
-
-    // ---------- compile, simulate, send ----------
-    let v0_msg = v0::Message::try_compile(&payer.pubkey(), &ixs, &merged_luts, latest_blockhash)?;
-    let vtx = VersionedTransaction::try_new(VersionedMessage::V0(v0_msg), &[&payer])?;
-
-    let sim = client
-        .simulate_transaction_with_config(
-            &vtx,
-            RpcSimulateTransactionConfig {
-                sig_verify: true,
-                replace_recent_blockhash: false,
-                commitment: Some(CommitmentConfig::processed()),
-                encoding: None,
-                accounts: None,
-                min_context_slot: None,
-                inner_instructions: true,
-            },
-        )
-        .await?;
-
-    if let Some(logs) = sim.value.logs.clone() {
-        println!("--- simulation logs ({} feeds) ---", FEEDS.len());
-        for l in logs {
-            println!("{l}");
-        }
-        println!("----------------------------------");
-    }
-    if let Some(err) = sim.value.err.clone() {
-        anyhow::bail!("simulation failed: {err:?}");
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].feeds.len(), 3);
     }
 
-    let sig = client.send_and_confirm_transaction(&vtx).await?;
-    println!("✅ Cranked {} feeds in one tx -> {sig}", FEEDS.len());
-    for f in FEEDS {
-        println!("  • {f}");
+    #[test]
+    fn splits_into_multiple_groups_once_the_cu_ceiling_is_reached() {
+        let payer = Keypair::new();
+        // CU_CEILING / PER_FEED_CU_ESTIMATE == 4 feeds per group (1.4M / 300k),
+        // so a 5th feed must spill into a second group.
+        let updates = (0..5)
+            .map(|_| dummy_update(Pubkey::new_unique(), &payer.pubkey(), 0))
+            .collect();
+
+        let groups =
+            pack_feed_updates(&payer, updates, PER_FEED_CU_ESTIMATE, Hash::default()).unwrap();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].feeds.len(), 4);
+        assert_eq!(groups[1].feeds.len(), 1);
     }
 
-    Ok(())
-}
+    #[test]
+    fn errors_when_a_single_feed_alone_exceeds_the_size_limit() {
+        let payer = Keypair::new();
+        let updates = vec![dummy_update(
+            Pubkey::new_unique(),
+            &payer.pubkey(),
+            PACKET_DATA_SIZE * 2,
+        )];
 
-// Minimal example w/ one feed
-// const FEED_PUBKEY: &str = "5htZ4vPKPjAEg8EJv6JHcaCetMM4XehZo8znQvrp6Ur3";
-
-// #[tokio::main]
-// async fn main() -> Result<()> {
-//     let rpc_url = std::env::var("RPC_URL")
-//         .unwrap_or_else(|_| "https://api.mainnet-beta.solana.com".to_string());
-//     let gateway_url = std::env::var("SWB_GATEWAY").unwrap_or_else(|_| {
-//         "https://92.222.100.182.xip.switchboard-oracles.xyz/mainnet".to_string()
-//     });
-
-//     let default_kp = format!(
-//         "{}/keys/staging-deploy.json",
-//         std::env::var("HOME").unwrap_or_else(|_| ".".to_string())
-//     );
-//     let keypair_path = std::env::var("KEYPAIR").unwrap_or(default_kp);
-//     let payer: Keypair = read_keypair_file(&keypair_path)
-//         .map_err(|e| anyhow::anyhow!("read_keypair_file({}): {e}", keypair_path))?;
-
-//     let feed = Pubkey::from_str(FEED_PUBKEY)?;
-
-//     let client = RpcClient::new_with_commitment(rpc_url, CommitmentConfig::confirmed());
-//     let ctx = SbContext::new();
-//     let gateway = Gateway::new(gateway_url);
-//     let crossbar = CrossbarClient::default();
-
-//     let (update_ix, _responses, _num_ok, luts) = PullFeed::fetch_update_ix(
-//         ctx.clone(),
-//         &client,
-//         FetchUpdateParams {
-//             feed,
-//             payer: payer.pubkey(),
-//             gateway: gateway.clone(),
-//             crossbar: Some(crossbar),
-//             num_signatures: Some(1),
-//             debug: Some(false),
-//         },
-//     )
-//     .await?;
-
-//     let latest_blockhash = client.get_latest_blockhash().await?;
-//     let compute_ixes = vec![
-//         ComputeBudgetInstruction::set_compute_unit_limit(1_200_000),
-//         ComputeBudgetInstruction::set_compute_unit_price(5_000),
-//     ];
-
-//     let mut ixs: Vec<Instruction> = compute_ixes;
-//     ixs.push(update_ix);
-
-//     let v0_msg = v0::Message::try_compile(&payer.pubkey(), &ixs, &luts, latest_blockhash)?;
-
-//     let vtx = VersionedTransaction::try_new(VersionedMessage::V0(v0_msg), &[&payer])?;
-//     let sim = client
-//         .simulate_transaction_with_config(
-//             &vtx,
-//             RpcSimulateTransactionConfig {
-//                 sig_verify: true,
-//                 replace_recent_blockhash: false,
-//                 commitment: Some(CommitmentConfig::processed()),
-//                 encoding: None,
-//                 accounts: None,
-//                 min_context_slot: None,
-//                 inner_instructions: true,
-//             },
-//         )
-//         .await?;
-//     if let Some(logs) = sim.value.logs.clone() {
-//         println!("--- simulation logs ---");
-//         for l in logs {
-//             println!("{l}");
-//         }
-//         println!("-----------------------");
-//     }
-//     if let Some(err) = sim.value.err.clone() {
-//         anyhow::bail!("simulation failed: {err:?}");
-//     }
-//     // END INSERT —>
-
-//     let sig = client.send_and_confirm_transaction(&vtx).await?;
-//     println!("✅ Cranked {feed} -> {sig}");
-//     Ok(())
-// }
+        let err =
+            pack_feed_updates(&payer, updates, PER_FEED_CU_ESTIMATE, Hash::default()).unwrap_err();
+
+        assert!(err.to_string().contains("exceeds the tx size limit"));
+    }
+}
@@ -0,0 +1,193 @@
+//! Latency histograms and counters for the crank loop's phases, plus an
+//! optional Prometheus-style `/metrics` endpoint so the process can be
+//! monitored when run as a daemon.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::Result;
+use solana_sdk::pubkey::Pubkey;
+
+/// Rolling set of latency samples (milliseconds) with percentiles computed
+/// on demand. Sample volumes here are small (one crank loop, a handful of
+/// feeds), so a capped sample vector is simpler than true fixed buckets.
+#[derive(Default)]
+pub struct Histogram {
+    samples_ms: Mutex<Vec<f64>>,
+}
+
+impl Histogram {
+    const MAX_SAMPLES: usize = 10_000;
+
+    pub fn record(&self, d: Duration) {
+        let mut samples = self.samples_ms.lock().unwrap();
+        if samples.len() >= Self::MAX_SAMPLES {
+            samples.remove(0);
+        }
+        samples.push(d.as_secs_f64() * 1000.0);
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples_ms.lock().unwrap().len()
+    }
+
+    /// (p50, p90, p99) in milliseconds; all zero if no samples yet.
+    pub fn percentiles(&self) -> (f64, f64, f64) {
+        let mut samples = self.samples_ms.lock().unwrap().clone();
+        if samples.is_empty() {
+            return (0.0, 0.0, 0.0);
+        }
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        (
+            percentile(&samples, 50.0),
+            percentile(&samples, 90.0),
+            percentile(&samples, 99.0),
+        )
+    }
+}
+
+fn percentile(sorted: &[f64], pct: f64) -> f64 {
+    let idx =
+        (((pct / 100.0) * (sorted.len() - 1) as f64).round() as usize).min(sorted.len() - 1);
+    sorted[idx]
+}
+
+/// Instrumentation for one process's worth of crank iterations.
+#[derive(Default)]
+pub struct Metrics {
+    pub fetch_update_ix: Histogram,
+    pub blockhash_fetch: Histogram,
+    pub simulation: Histogram,
+    pub send_and_confirm: Histogram,
+    pub sim_failures: AtomicU64,
+    pub tx_confirmed: AtomicU64,
+    pub tx_dropped: AtomicU64,
+    // feed pubkey (base58) -> most recent _num_ok from fetch_update_ix
+    feed_num_ok: Mutex<HashMap<String, u64>>,
+}
+
+impl Metrics {
+    pub fn record_num_ok(&self, feed: Pubkey, num_ok: u32) {
+        self.feed_num_ok
+            .lock()
+            .unwrap()
+            .insert(feed.to_string(), num_ok as u64);
+    }
+
+    /// Prints a one-block summary to stdout; called on a timer in daemon/grpc
+    /// mode and once before exit in one-shot mode.
+    pub fn report(&self) {
+        println!("--- metrics report ---");
+        for (name, hist) in [
+            ("fetch_update_ix", &self.fetch_update_ix),
+            ("blockhash_fetch", &self.blockhash_fetch),
+            ("simulation", &self.simulation),
+            ("send_and_confirm", &self.send_and_confirm),
+        ] {
+            let (p50, p90, p99) = hist.percentiles();
+            println!(
+                "  {name}: n={} p50={p50:.1}ms p90={p90:.1}ms p99={p99:.1}ms",
+                hist.count()
+            );
+        }
+        println!(
+            "  sim_failures={} tx_confirmed={} tx_dropped={}",
+            self.sim_failures.load(Ordering::Relaxed),
+            self.tx_confirmed.load(Ordering::Relaxed),
+            self.tx_dropped.load(Ordering::Relaxed),
+        );
+        for (feed, num_ok) in self.feed_num_ok.lock().unwrap().iter() {
+            println!("  feed {feed} num_ok={num_ok}");
+        }
+        println!("-----------------------");
+    }
+
+    /// Renders current state in Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        for (name, hist) in [
+            ("fetch_update_ix", &self.fetch_update_ix),
+            ("blockhash_fetch", &self.blockhash_fetch),
+            ("simulation", &self.simulation),
+            ("send_and_confirm", &self.send_and_confirm),
+        ] {
+            let (p50, p90, p99) = hist.percentiles();
+            out.push_str(&format!(
+                "# TYPE swb_crank_{name}_latency_ms gauge\n\
+                 swb_crank_{name}_latency_ms{{quantile=\"0.5\"}} {p50}\n\
+                 swb_crank_{name}_latency_ms{{quantile=\"0.9\"}} {p90}\n\
+                 swb_crank_{name}_latency_ms{{quantile=\"0.99\"}} {p99}\n"
+            ));
+        }
+        out.push_str(&format!(
+            "# TYPE swb_crank_sim_failures_total counter\n\
+             swb_crank_sim_failures_total {}\n\
+             # TYPE swb_crank_tx_confirmed_total counter\n\
+             swb_crank_tx_confirmed_total {}\n\
+             # TYPE swb_crank_tx_dropped_total counter\n\
+             swb_crank_tx_dropped_total {}\n",
+            self.sim_failures.load(Ordering::Relaxed),
+            self.tx_confirmed.load(Ordering::Relaxed),
+            self.tx_dropped.load(Ordering::Relaxed),
+        ));
+        out.push_str("# TYPE swb_crank_feed_num_ok gauge\n");
+        for (feed, num_ok) in self.feed_num_ok.lock().unwrap().iter() {
+            out.push_str(&format!(
+                "swb_crank_feed_num_ok{{feed=\"{feed}\"}} {num_ok}\n"
+            ));
+        }
+        out
+    }
+}
+
+/// Serves `GET /metrics` over plain HTTP on `addr` until the process exits.
+pub async fn serve_prometheus(addr: &str, metrics: std::sync::Arc<Metrics>) -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    println!("metrics: serving Prometheus endpoint on http://{addr}/metrics");
+
+    loop {
+        let (mut socket, _) = listener.accept().await?;
+        let metrics = std::sync::Arc::clone(&metrics);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            // discard the request; we only ever serve one body.
+            let _ = socket.read(&mut buf).await;
+            let body = metrics.render_prometheus();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_on_empty_histogram_are_zero() {
+        let hist = Histogram::default();
+        assert_eq!(hist.percentiles(), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn percentiles_on_single_sample_all_equal_that_sample() {
+        let hist = Histogram::default();
+        hist.record(Duration::from_millis(42));
+        assert_eq!(hist.percentiles(), (42.0, 42.0, 42.0));
+    }
+
+    #[test]
+    fn percentile_boundaries_pick_min_and_max() {
+        let sorted = vec![1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 100.0), 5.0);
+    }
+}